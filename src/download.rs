@@ -1,14 +1,13 @@
-use serde::Deserialize;
-use crate::{Data, Result, Error};
+use crate::tar;
+use crate::{Data, DataKind, Error, Result};
 use directories::BaseDirs;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
 use std::fs;
-use std::path::Path;
-
-#[derive(Deserialize, Debug)]
-struct GithubFileData {
-    name: String,
-    download_url: String,
-}
+use std::path::{Path, PathBuf};
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
@@ -18,47 +17,493 @@ impl From<minreq::Error> for Error {
     }
 }
 
+pub enum Fetched {
+    Unchanged,
+    /// Carries the new ETag (or other cache key), if the source has one.
+    Updated(String),
+}
+
+/// Lets `Sources` passed to [`Data::with_download`]/[`Data::update`] point
+/// at forks, pinned commits, or vendored mirrors instead of this crate's
+/// hardcoded GitHub repos.
+pub trait DataSource {
+    fn fetch(&self, kind: DataKind, dest: &Path, etag: Option<&str>) -> Result<Fetched>;
+}
+
+pub type Sources = HashMap<DataKind, Box<dyn DataSource>>;
+
+fn dir_name(kind: DataKind) -> &'static str {
+    match kind {
+        DataKind::Layout => "layouts",
+        DataKind::Keyboard => "metrics",
+        DataKind::Corpus => "corpora",
+    }
+}
+
+fn token_from_env() -> Option<String> {
+    env::var("GITHUB_TOKEN")
+        .or_else(|_| env::var("KM_DATA_TOKEN"))
+        .ok()
+}
+
+/// The source `Data::with_download` uses by default, pointed at `semilin`'s
+/// layouts/metrics/corpora repos.
+pub struct GithubSource {
+    pub owner: String,
+    pub repo: String,
+    pub reference: String,
+    pub token: Option<String>,
+}
+
+impl GithubSource {
+    pub fn new(owner: impl Into<String>, repo: impl Into<String>) -> Self {
+        GithubSource {
+            owner: owner.into(),
+            repo: repo.into(),
+            reference: "HEAD".to_string(),
+            token: token_from_env(),
+        }
+    }
+
+    pub fn reference(mut self, reference: impl Into<String>) -> Self {
+        self.reference = reference.into();
+        self
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+}
+
+impl DataSource for GithubSource {
+    // Fetches the whole repo as one tarball rather than walking the contents
+    // API directory-by-directory, so there's no `Link: …rel="next"` listing
+    // to paginate through — the single request always returns everything.
+    fn fetch(&self, _kind: DataKind, dest: &Path, etag: Option<&str>) -> Result<Fetched> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/tarball/{}",
+            self.owner, self.repo, self.reference
+        );
+        let mut req = get(&url, self.token.as_deref());
+        if let Some(etag) = etag {
+            req = req.with_header("If-None-Match", etag);
+        }
+        fetch_tarball(req, dest, etag)
+    }
+}
+
+/// Copies an existing local directory tree in as a repo's data.
+pub struct LocalSource {
+    pub path: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LocalSource { path: path.into() }
+    }
+}
+
+impl DataSource for LocalSource {
+    fn fetch(&self, _kind: DataKind, dest: &Path, _etag: Option<&str>) -> Result<Fetched> {
+        copy_dir(&self.path, dest)?;
+        Ok(Fetched::Updated(String::new()))
+    }
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest).map_err(Error::DirCreate)?;
+    for entry in fs::read_dir(src).map_err(Error::DirRead)? {
+        let entry = entry.map_err(Error::DirRead)?;
+        let path = entry.path();
+        let target = dest.join(entry.file_name());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target).map_err(Error::FileWrite)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetches a gzipped tarball from an arbitrary URL, for mirrors that don't
+/// live on GitHub at all.
+pub struct UrlSource {
+    pub url: String,
+}
+
+impl UrlSource {
+    pub fn new(url: impl Into<String>) -> Self {
+        UrlSource { url: url.into() }
+    }
+}
+
+impl DataSource for UrlSource {
+    fn fetch(&self, _kind: DataKind, dest: &Path, etag: Option<&str>) -> Result<Fetched> {
+        let mut req = get(&self.url, None);
+        if let Some(etag) = etag {
+            req = req.with_header("If-None-Match", etag);
+        }
+        fetch_tarball(req, dest, etag)
+    }
+}
+
+fn fetch_tarball(req: minreq::Request, dest: &Path, etag: Option<&str>) -> Result<Fetched> {
+    let resp = send(req)?;
+    if etag.is_some() && resp.status_code == 304 {
+        return Ok(Fetched::Unchanged);
+    }
+    let new_etag = resp.headers.get("etag").cloned().unwrap_or_default();
+    let mut decoder = GzDecoder::new(resp.as_bytes());
+    tar::extract(&mut decoder, dest, &tar::Options::default())?;
+    Ok(Fetched::Updated(new_etag))
+}
+
+fn default_sources() -> Sources {
+    let mut sources: Sources = HashMap::new();
+    sources.insert(
+        DataKind::Layout,
+        Box::new(GithubSource::new("semilin", "km_layouts")),
+    );
+    sources.insert(
+        DataKind::Keyboard,
+        Box::new(GithubSource::new("semilin", "km_metric_data")),
+    );
+    sources.insert(
+        DataKind::Corpus,
+        Box::new(GithubSource::new("semilin", "km_corpora")),
+    );
+    sources
+}
+
+/// The ETag each tracked kind was last fetched at, so `update()` can issue
+/// conditional requests and skip sources that haven't changed.
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    etags: HashMap<String, String>,
+}
+
+impl Manifest {
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join("manifest.json")
+    }
+
+    fn load(data_dir: &Path) -> Result<Self> {
+        let path = Self::path(data_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let b = fs::read_to_string(&path).map_err(Error::FileRead)?;
+        serde_json::from_str(&b).map_err(|_| Error::ManifestParse)
+    }
+
+    fn save(&self, data_dir: &Path) -> Result<()> {
+        let b = serde_json::to_string_pretty(self).map_err(|_| Error::ManifestParse)?;
+        fs::write(Self::path(data_dir), b).map_err(Error::FileWrite)
+    }
+}
+
 impl Data {
-    pub fn with_download() -> Result<Self> {
+    /// `sources` overrides where each `DataKind`'s files come from,
+    /// defaulting to `semilin`'s GitHub repos.
+    pub fn with_download(sources: Option<Sources>) -> Result<Self> {
         let base_dirs = BaseDirs::new().ok_or(Error::BaseDirs)?;
         let data_dir = base_dirs.data_dir().join("keymeow");
         if !data_dir.exists() {
-            download_files(&data_dir)?;
+            download_files(&data_dir, &sources.unwrap_or_else(default_sources))?;
         }
         Self::new()
     }
+
+    /// Re-fetches every kind in `sources` (defaulting to `semilin`'s GitHub
+    /// repos), skipping any whose contents haven't changed.
+    pub fn update(&self, sources: Option<Sources>) -> Result<()> {
+        let sources = sources.unwrap_or_else(default_sources);
+        let mut manifest = Manifest::load(&self.data_dir)?;
+        let mut lock = Lock::load(&self.data_dir)?;
+        for (kind, source) in &sources {
+            let key = kind.to_string();
+            let etag = manifest.etags.get(&key).map(String::as_str);
+            let dir = self.data_dir.join(dir_name(*kind));
+            if let Fetched::Updated(etag) = source.fetch(*kind, &dir, etag)? {
+                manifest.etags.insert(key, etag);
+                lock.record_dir(&self.data_dir, &dir)?;
+            }
+        }
+        manifest.save(&self.data_dir)?;
+        lock.save(&self.data_dir)
+    }
+}
+
+pub fn download_files(data_dir: &Path, sources: &Sources) -> Result<()> {
+    let mut manifest = Manifest::default();
+    let mut lock = Lock::default();
+    for (kind, source) in sources {
+        let dir = data_dir.join(dir_name(*kind));
+        fs::create_dir_all(&dir).map_err(Error::DirCreate)?;
+        if let Fetched::Updated(etag) = source.fetch(*kind, &dir, None)? {
+            manifest.etags.insert(kind.to_string(), etag);
+            lock.record_dir(data_dir, &dir)?;
+        }
+    }
+    manifest.save(data_dir)?;
+    lock.save(data_dir)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
 }
 
-pub fn download_files(data_dir: &Path) -> Result<()> {
-    download_repo(
-        &data_dir.join("layouts"),
-        "https://api.github.com/repos/semilin/km_layouts/contents/",
-    )?;
-    download_repo(
-        &data_dir.join("metrics"),
-        "https://api.github.com/repos/semilin/km_metric_data/contents/",
-    )?;
-    download_repo(
-        &data_dir.join("corpora"),
-        "https://api.github.com/repos/semilin/km_corpora/contents/",
-    )?;
+fn kind_for_rel_path(rel: &str) -> Option<DataKind> {
+    let dir = rel.split('/').next()?;
+    match dir {
+        "layouts" => Some(DataKind::Layout),
+        "metrics" => Some(DataKind::Keyboard),
+        "corpora" => Some(DataKind::Corpus),
+        _ => None,
+    }
+}
+
+fn walk_files(dir: &Path, base: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(Error::DirRead)? {
+        let entry = entry.map_err(Error::DirRead)?;
+        let path = entry.path();
+        if path.is_dir() {
+            walk_files(&path, base, out)?;
+        } else {
+            out.push(path.strip_prefix(base).unwrap_or(&path).to_path_buf());
+        }
+    }
     Ok(())
 }
 
-fn get(url: &str) -> minreq::Request {
-    minreq::get(url)
-        .with_header("User-Agent", APP_USER_AGENT)
-        .with_timeout(8)
+#[derive(Serialize, Deserialize, Clone)]
+struct FileDigest {
+    sha256: String,
+    len: u64,
+}
+
+/// The SHA-256 digest and length of every file fetched, so `Data::verify`
+/// can detect truncated downloads or on-disk corruption without
+/// deserializing anything.
+#[derive(Serialize, Deserialize, Default)]
+struct Lock {
+    files: HashMap<String, FileDigest>,
+}
+
+impl Lock {
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join("lock.json")
+    }
+
+    fn load(data_dir: &Path) -> Result<Self> {
+        let path = Self::path(data_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let b = fs::read_to_string(&path).map_err(Error::FileRead)?;
+        serde_json::from_str(&b).map_err(|_| Error::LockParse)
+    }
+
+    fn save(&self, data_dir: &Path) -> Result<()> {
+        let b = serde_json::to_string_pretty(self).map_err(|_| Error::LockParse)?;
+        fs::write(Self::path(data_dir), b).map_err(Error::FileWrite)
+    }
+
+    /// Re-hashes every file now present under `dir`, replacing whatever was
+    /// previously recorded for that subtree.
+    fn record_dir(&mut self, data_dir: &Path, dir: &Path) -> Result<()> {
+        let prefix = dir
+            .strip_prefix(data_dir)
+            .unwrap_or(dir)
+            .to_string_lossy()
+            .into_owned();
+        self.files.retain(|k, _| !k.starts_with(&prefix));
+
+        let mut files = Vec::new();
+        walk_files(dir, data_dir, &mut files)?;
+        for rel in files {
+            let bytes = fs::read(data_dir.join(&rel)).map_err(Error::FileRead)?;
+            self.files.insert(
+                rel.to_string_lossy().into_owned(),
+                FileDigest {
+                    sha256: sha256_hex(&bytes),
+                    len: bytes.len() as u64,
+                },
+            );
+        }
+        Ok(())
+    }
 }
 
-fn download_repo(directory: &Path, url: &str) -> Result<()> {
-    let resp = get(url).send();
-    let data = resp?.json::<Vec<GithubFileData>>()?;
+fn digest_matches(path: &Path, expected: &FileDigest) -> bool {
+    match fs::read(path) {
+        Ok(bytes) => bytes.len() as u64 == expected.len && sha256_hex(&bytes) == expected.sha256,
+        Err(_) => false,
+    }
+}
+
+/// Untracked files (e.g. from a pre-lockfile download) pass trivially.
+pub(crate) fn check_integrity(data_dir: &Path, kind: DataKind, path: &Path) -> Result<()> {
+    let lock = Lock::load(data_dir)?;
+    let rel = path
+        .strip_prefix(data_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned();
+    let Some(expected) = lock.files.get(&rel) else {
+        return Ok(());
+    };
+    if digest_matches(path, expected) {
+        Ok(())
+    } else {
+        Err(Error::IntegrityMismatch(kind, rel))
+    }
+}
 
-    for filedata in data {
-        if let Ok(contents) = get(&filedata.download_url).send() {
-            fs::write(directory.join(filedata.name), contents.as_bytes()).map_err(Error::FileWrite)?;
+pub(crate) fn verify(data_dir: &Path) -> Result<Vec<(DataKind, String)>> {
+    let lock = Lock::load(data_dir)?;
+    let mut mismatches = Vec::new();
+    for (rel, expected) in &lock.files {
+        let Some(kind) = kind_for_rel_path(rel) else {
+            continue;
         };
+        if !digest_matches(&data_dir.join(rel), expected) {
+            mismatches.push((kind, rel.clone()));
+        }
+    }
+    Ok(mismatches)
+}
+
+fn get(url: &str, token: Option<&str>) -> minreq::Request {
+    let mut req = minreq::get(url)
+        .with_header("User-Agent", APP_USER_AGENT)
+        .with_timeout(8);
+    if let Some(token) = token {
+        req = req.with_header("Authorization", format!("Bearer {token}"));
+    }
+    req
+}
+
+fn send(req: minreq::Request) -> Result<minreq::Response> {
+    let resp = req.send()?;
+    if matches!(resp.status_code, 403 | 429)
+        && resp.headers.get("x-ratelimit-remaining").map(String::as_str) == Some("0")
+    {
+        let reset = resp
+            .headers
+            .get("x-ratelimit-reset")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        return Err(Error::RateLimited { reset });
+    }
+    // 304 is only meaningful to callers making a conditional request; they
+    // check for it themselves. Anything else outside 2xx is a real failure
+    // (missing repo, bad token, GitHub error page) that must not be piped
+    // into the gzip/tar decoder as if it were archive data.
+    if !(resp.status_code == 304 || (200..300).contains(&resp.status_code)) {
+        return Err(Error::HttpStatus(resp.status_code));
+    }
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "km_data_download_test_{label}_{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sha256_hex_matches_known_vector() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn record_dir_then_check_integrity_round_trips() {
+        let data_dir = temp_dir("roundtrip");
+        let layouts = data_dir.join("layouts");
+        fs::create_dir_all(&layouts).unwrap();
+        fs::write(layouts.join("qwerty.json"), b"{}").unwrap();
+
+        let mut lock = Lock::default();
+        lock.record_dir(&data_dir, &layouts).unwrap();
+        lock.save(&data_dir).unwrap();
+
+        assert!(check_integrity(&data_dir, DataKind::Layout, &layouts.join("qwerty.json")).is_ok());
+
+        fs::write(layouts.join("qwerty.json"), b"corrupted").unwrap();
+        assert!(matches!(
+            check_integrity(&data_dir, DataKind::Layout, &layouts.join("qwerty.json")),
+            Err(Error::IntegrityMismatch(DataKind::Layout, _))
+        ));
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn verify_reports_only_mismatched_files() {
+        let data_dir = temp_dir("verify");
+        let corpora = data_dir.join("corpora");
+        fs::create_dir_all(&corpora).unwrap();
+        fs::write(corpora.join("english.msgpack"), b"corpus-a").unwrap();
+        fs::write(corpora.join("german.msgpack"), b"corpus-b").unwrap();
+
+        let mut lock = Lock::default();
+        lock.record_dir(&data_dir, &corpora).unwrap();
+        lock.save(&data_dir).unwrap();
+
+        fs::write(corpora.join("german.msgpack"), b"truncated").unwrap();
+
+        let mismatches = verify(&data_dir).unwrap();
+        assert_eq!(mismatches, vec![(DataKind::Corpus, "corpora/german.msgpack".to_string())]);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn local_source_copies_nested_files_into_dest() {
+        let src = temp_dir("local_source_src");
+        fs::write(src.join("qwerty.json"), b"{}").unwrap();
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub").join("dvorak.json"), b"{}").unwrap();
+
+        let dest = temp_dir("local_source_dest");
+        fs::remove_dir_all(&dest).unwrap();
+
+        let fetched = LocalSource::new(&src)
+            .fetch(DataKind::Layout, &dest, None)
+            .unwrap();
+        assert!(matches!(fetched, Fetched::Updated(ref etag) if etag.is_empty()));
+        assert_eq!(fs::read(dest.join("qwerty.json")).unwrap(), b"{}");
+        assert_eq!(fs::read(dest.join("sub").join("dvorak.json")).unwrap(), b"{}");
+
+        fs::remove_dir_all(&src).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn check_integrity_passes_for_untracked_file() {
+        let data_dir = temp_dir("untracked");
+        let layouts = data_dir.join("layouts");
+        fs::create_dir_all(&layouts).unwrap();
+        fs::write(layouts.join("new.json"), b"{}").unwrap();
+
+        assert!(check_integrity(&data_dir, DataKind::Layout, &layouts.join("new.json")).is_ok());
+
+        fs::remove_dir_all(&data_dir).unwrap();
     }
-    Ok(())
 }
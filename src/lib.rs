@@ -1,11 +1,15 @@
 #[cfg(feature = "download")]
 mod download;
+#[cfg(feature = "download")]
+mod tar;
+#[cfg(feature = "download")]
+pub use download::{DataSource, Fetched, GithubSource, LocalSource, Sources, UrlSource};
 use directories::BaseDirs;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::{error, fmt, fs, io};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DataKind {
     Corpus,
     Keyboard,
@@ -30,6 +34,18 @@ pub enum Error {
     JsonDeserialize(serde_json::Error),
     #[cfg(feature = "download")]
     Download(minreq::Error),
+    #[cfg(feature = "download")]
+    ManifestParse,
+    #[cfg(feature = "download")]
+    LockParse,
+    #[cfg(feature = "download")]
+    HttpStatus(i32),
+    #[cfg(feature = "download")]
+    RateLimited { reset: u64 },
+    #[cfg(feature = "download")]
+    IntegrityMismatch(DataKind, String),
+    #[cfg(feature = "corpora")]
+    RmpSerialize(rmp_serde::encode::Error),
 }
 
 impl fmt::Display for Error {
@@ -47,6 +63,22 @@ impl fmt::Display for Error {
             Error::JsonDeserialize(..) => write!(f, "error deserializing json data"),
             #[cfg(feature = "download")]
             Error::Download(..) => write!(f, "error downloading data"),
+            #[cfg(feature = "download")]
+            Error::ManifestParse => write!(f, "manifest.json is corrupt or unreadable"),
+            #[cfg(feature = "download")]
+            Error::LockParse => write!(f, "lock.json is corrupt or unreadable"),
+            #[cfg(feature = "download")]
+            Error::HttpStatus(status) => write!(f, "request failed with HTTP status {status}"),
+            #[cfg(feature = "download")]
+            Error::RateLimited { reset } => {
+                write!(f, "rate limited by GitHub, resets at unix time {reset}")
+            }
+            #[cfg(feature = "download")]
+            Error::IntegrityMismatch(kind, name) => {
+                write!(f, "{kind} file `{name}` does not match lock.json")
+            }
+            #[cfg(feature = "corpora")]
+            Error::RmpSerialize(..) => write!(f, "error serializing messagepack data"),
         }
     }
 }
@@ -62,6 +94,8 @@ impl error::Error for Error {
             Error::JsonDeserialize(ref e) => Some(e),
             #[cfg(feature = "download")]
             Error::Download(ref e) => Some(e),
+            #[cfg(feature = "corpora")]
+            Error::RmpSerialize(ref e) => Some(e),
             _ => None,
         }
     }
@@ -150,4 +184,98 @@ impl Data {
         let b = fs::read_to_string(path).map_err(Error::FileRead)?;
         serde_json::from_str(&b).map_err(Error::JsonDeserialize)
     }
+    /// Like [`Data::get_corpus`], but first checks the file against
+    /// `lock.json`, failing with `Error::IntegrityMismatch` instead of
+    /// risking an opaque deserialize error on a truncated download.
+    #[cfg(all(feature = "corpora", feature = "download"))]
+    pub fn get_corpus_verified(&self, s: &str) -> Result<keycat::Corpus> {
+        let path = self
+            .corpora
+            .get(s)
+            .ok_or_else(|| Error::Locate(DataKind::Corpus, s.to_owned()))?;
+        download::check_integrity(&self.data_dir, DataKind::Corpus, path)?;
+        self.get_corpus(s)
+    }
+    /// Like [`Data::get_metrics`], but first checks the file against
+    /// `lock.json`.
+    #[cfg(all(feature = "keyboards", feature = "download"))]
+    pub fn get_metrics_verified(&self, s: &str) -> Result<keymeow::MetricData> {
+        let path = self
+            .keyboards
+            .get(s)
+            .ok_or_else(|| Error::Locate(DataKind::Keyboard, s.to_owned()))?;
+        download::check_integrity(&self.data_dir, DataKind::Keyboard, path)?;
+        self.get_metrics(s)
+    }
+    /// Like [`Data::get_layout`], but first checks the file against
+    /// `lock.json`.
+    #[cfg(all(feature = "layouts", feature = "download"))]
+    pub fn get_layout_verified(&self, s: &str) -> Result<keymeow::LayoutData> {
+        let path = self
+            .layouts
+            .get(s)
+            .ok_or_else(|| Error::Locate(DataKind::Layout, s.to_owned()))?;
+        download::check_integrity(&self.data_dir, DataKind::Layout, path)?;
+        let b = fs::read_to_string(path).map_err(Error::FileRead)?;
+        serde_json::from_str(&b).map_err(Error::JsonDeserialize)
+    }
+    /// Rehashes every file recorded in `lock.json` and returns the
+    /// `(kind, path)` of each one that no longer matches, without
+    /// deserializing anything.
+    #[cfg(feature = "download")]
+    pub fn verify(&self) -> Result<Vec<(DataKind, String)>> {
+        download::verify(&self.data_dir)
+    }
+    /// Reads each of `text_sources` whole into a `keycat::Corpus` tallied over
+    /// [`DEFAULT_CHARSET`], writes it into the `corpora` dir under `name`,
+    /// and records it in `self.corpora` so `get_corpus(name)` works right away.
+    #[cfg(feature = "corpora")]
+    pub fn build_corpus(&mut self, name: &str, text_sources: &[PathBuf]) -> Result<keycat::Corpus> {
+        let mut corpus = keycat::Corpus::new(DEFAULT_CHARSET.chars().collect());
+        for path in text_sources {
+            let text = fs::read_to_string(path).map_err(Error::FileRead)?;
+            corpus.add_str(&text);
+        }
+        let bytes = rmp_serde::to_vec(&corpus).map_err(Error::RmpSerialize)?;
+        let path = self.data_dir.join("corpora").join(format!("{name}.msgpack"));
+        fs::write(&path, bytes).map_err(Error::FileWrite)?;
+        self.corpora.insert(name.to_owned(), path);
+        Ok(corpus)
+    }
+}
+
+/// Character set [`Data::build_corpus`] tallies frequencies over.
+#[cfg(feature = "corpora")]
+const DEFAULT_CHARSET: &str = "abcdefghijklmnopqrstuvwxyz,.;'/ ";
+
+#[cfg(all(test, feature = "corpora"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_corpus_round_trips_through_get_corpus() {
+        let data_dir = std::env::temp_dir().join(format!(
+            "km_data_lib_test_build_corpus_{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(data_dir.join("corpora")).unwrap();
+
+        let text_path = data_dir.join("sample.txt");
+        fs::write(&text_path, "the quick brown fox").unwrap();
+
+        let mut data = Data {
+            data_dir: data_dir.clone(),
+            corpora: HashMap::new(),
+            #[cfg(feature = "keyboards")]
+            keyboards: HashMap::new(),
+            #[cfg(feature = "layouts")]
+            layouts: HashMap::new(),
+        };
+
+        data.build_corpus("sample", &[text_path]).unwrap();
+        assert!(data.get_corpus("sample").is_ok());
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
 }
@@ -0,0 +1,221 @@
+use crate::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const BLOCK_SIZE: usize = 512;
+
+pub(crate) struct Options {
+    /// Mirrors `tar --strip-components`.
+    pub strip_components: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            strip_components: 1,
+        }
+    }
+}
+
+fn parse_octal(field: &[u8]) -> u64 {
+    let s = std::str::from_utf8(field).unwrap_or("");
+    u64::from_str_radix(s.trim_matches(|c: char| c == '\0' || c.is_whitespace()), 8).unwrap_or(0)
+}
+
+fn entry_name(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Drops `.` segments and rejects any entry with a `..` segment, so an entry
+/// can't escape `dest` via `dest.join(path)`.
+fn stripped_path(name: &str, strip_components: u32) -> Option<PathBuf> {
+    let remaining: Vec<&str> = name
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .skip(strip_components as usize)
+        .collect();
+    if remaining.is_empty() || remaining.contains(&"..") {
+        None
+    } else {
+        Some(remaining.iter().collect())
+    }
+}
+
+/// Fills `buf` and returns `Ok(true)`, or returns `Ok(false)` on a clean EOF
+/// that landed exactly on a block boundary (nothing read yet). A read error
+/// or an EOF partway through the block is a corrupt/truncated stream and is
+/// propagated instead of being mistaken for the archive's end.
+fn read_block(reader: &mut impl Read, buf: &mut [u8; BLOCK_SIZE]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::FileRead(e)),
+        }
+    }
+    match filled {
+        0 => Ok(false),
+        n if n == buf.len() => Ok(true),
+        _ => Err(Error::FileRead(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "tar entry header truncated",
+        ))),
+    }
+}
+
+pub(crate) fn extract(reader: &mut impl Read, dest: &Path, opts: &Options) -> Result<(), Error> {
+    let mut header = [0u8; BLOCK_SIZE];
+    loop {
+        if !read_block(reader, &mut header)? {
+            break;
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let name = entry_name(&header[0..100]);
+        let size = parse_octal(&header[124..136]) as usize;
+        let typeflag = header[156];
+        let blocks = size.div_ceil(BLOCK_SIZE);
+
+        let mut contents = vec![0u8; blocks * BLOCK_SIZE];
+        if blocks > 0 {
+            reader.read_exact(&mut contents).map_err(Error::FileRead)?;
+        }
+        contents.truncate(size);
+
+        if let Some(path) = stripped_path(&name, opts.strip_components) {
+            let path = dest.join(path);
+            match typeflag {
+                b'5' => {
+                    fs::create_dir_all(&path).map_err(Error::DirCreate)?;
+                }
+                b'0' | 0 => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).map_err(Error::DirCreate)?;
+                    }
+                    fs::write(&path, &contents).map_err(Error::FileWrite)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_leading_component() {
+        assert_eq!(
+            stripped_path("repo-abc123/src/lib.rs", 1),
+            Some(PathBuf::from("src/lib.rs"))
+        );
+    }
+
+    #[test]
+    fn drops_entries_emptied_by_stripping() {
+        assert_eq!(stripped_path("repo-abc123", 1), None);
+    }
+
+    #[test]
+    fn rejects_parent_dir_traversal() {
+        assert_eq!(stripped_path("repo-abc123/../../../etc/passwd", 1), None);
+        assert_eq!(stripped_path("../../etc/passwd", 0), None);
+    }
+
+    #[test]
+    fn ignores_current_dir_components() {
+        assert_eq!(
+            stripped_path("repo-abc123/./src/./lib.rs", 1),
+            Some(PathBuf::from("src/lib.rs"))
+        );
+    }
+
+    fn pad_block(mut bytes: Vec<u8>) -> Vec<u8> {
+        let blocks = bytes.len().div_ceil(BLOCK_SIZE);
+        bytes.resize(blocks * BLOCK_SIZE, 0);
+        bytes
+    }
+
+    fn header(name: &str, size: usize, typeflag: u8) -> Vec<u8> {
+        let mut block = vec![0u8; BLOCK_SIZE];
+        block[0..name.len()].copy_from_slice(name.as_bytes());
+        let size_field = format!("{size:011o}\0");
+        block[124..124 + size_field.len()].copy_from_slice(size_field.as_bytes());
+        block[156] = typeflag;
+        block
+    }
+
+    #[test]
+    fn extracts_regular_file_and_skips_traversal_entry() {
+        let dir = std::env::temp_dir().join(format!(
+            "km_data_tar_test_{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut archive = Vec::new();
+        archive.extend(header("pkg-1/src/lib.rs", 5, b'0'));
+        archive.extend(pad_block(b"hello".to_vec()));
+        archive.extend(header("pkg-1/../../escape.rs", 4, b'0'));
+        archive.extend(pad_block(b"evil".to_vec()));
+        archive.extend(vec![0u8; BLOCK_SIZE * 2]);
+
+        extract(
+            &mut archive.as_slice(),
+            &dir,
+            &Options {
+                strip_components: 1,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(dir.join("src/lib.rs")).unwrap(), b"hello");
+        assert!(!dir.parent().unwrap().parent().unwrap().join("escape.rs").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("decode failure"))
+        }
+    }
+
+    #[test]
+    fn propagates_read_errors_instead_of_treating_them_as_eof() {
+        let dir = std::env::temp_dir().join(format!(
+            "km_data_tar_test_err_{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let result = extract(&mut FailingReader, &dir, &Options::default());
+        assert!(matches!(result, Err(Error::FileRead(_))));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn errors_on_header_truncated_partway_through_block() {
+        let dir = std::env::temp_dir().join(format!(
+            "km_data_tar_test_trunc_{:?}",
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut archive = header("pkg-1/src/lib.rs", 5, b'0');
+        archive.truncate(BLOCK_SIZE / 2);
+
+        let result = extract(&mut archive.as_slice(), &dir, &Options::default());
+        assert!(matches!(result, Err(Error::FileRead(_))));
+    }
+}